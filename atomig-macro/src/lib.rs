@@ -0,0 +1,189 @@
+//! `#[derive(Atom)]`, the companion proc-macro crate for `atomig`.
+//!
+//! Implementing `Atom` by hand means writing matching `pack`/`unpack`
+//! methods and picking the right `Impl` width yourself; this crate
+//! generates that boilerplate for the two shapes that come up in
+//! practice: fieldless `#[repr(uN)]` enums and single-field newtype
+//! structs. See `atomig`'s docs for usage; this crate has no public API
+//! of its own beyond the derive macro.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DataEnum, DataStruct, DeriveInput, Fields};
+
+#[proc_macro_derive(Atom)]
+pub fn derive_atom(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let expanded = match &input.data {
+        Data::Enum(data) => derive_for_enum(&input, data),
+        Data::Struct(data) => derive_for_newtype(&input, data),
+        Data::Union(data) => syn::Error::new_spanned(
+            data.union_token,
+            "`#[derive(Atom)]` doesn't support unions",
+        )
+        .to_compile_error(),
+    };
+    expanded.into()
+}
+
+/// Generates `Atom` for a fieldless `#[repr(uN)]` enum: `pack` is the
+/// discriminant cast to the repr integer, and `unpack` matches each
+/// discriminant back to its variant.
+///
+/// The generated impl is `impl const Atom`, since both bodies are
+/// const-fn-legal (`self as #repr` and `unreachable!()`) -- this lets
+/// `Atomic::new` stay a `const fn` for derived enums too. As with any
+/// `impl const Trait`, the crate invoking `#[derive(Atom)]` must enable
+/// `#![feature(const_trait_impl)]` itself for this to compile.
+fn derive_for_enum(input: &DeriveInput, data: &DataEnum) -> TokenStream2 {
+    let name = &input.ident;
+
+    let repr = match repr_ident(input) {
+        Ok(repr) => repr,
+        Err(err) => return err.to_compile_error(),
+    };
+    let impl_ty = match atomic_ident_for_repr(&repr) {
+        Ok(ident) => ident,
+        Err(err) => return err.to_compile_error(),
+    };
+
+    for variant in &data.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return syn::Error::new_spanned(
+                variant,
+                "`#[derive(Atom)]` only supports fieldless enum variants",
+            )
+            .to_compile_error();
+        }
+    }
+
+    let variant_idents: Vec<_> = data.variants.iter().map(|v| &v.ident).collect();
+
+    quote! {
+        impl const ::atomig::Atom for #name {
+            type Impl = ::core::sync::atomic::#impl_ty;
+
+            fn pack(self) -> #repr {
+                self as #repr
+            }
+
+            // SAFETY precondition: `src` must be a bit pattern previously
+            // produced by `pack`, i.e. one of `#name`'s own discriminants
+            // -- which holds for any `src` that actually came out of the
+            // `Atomic<#name>` it was packed into.
+            //
+            // The bare `unreachable!()` (no message) is deliberate: the
+            // formatted-message form of `unreachable!()`/`panic!()` calls
+            // into `core::fmt`, which isn't const-fn-legal, and this impl
+            // is `impl const Atom`.
+            fn unpack(src: #repr) -> Self {
+                #(
+                    if src == (#name::#variant_idents as #repr) {
+                        return #name::#variant_idents;
+                    }
+                )*
+                unreachable!()
+            }
+        }
+    }
+}
+
+/// Generates `Atom` for a single-field newtype struct, forwarding
+/// `pack`/`unpack`/`Impl` to the wrapped field's own `Atom` impl.
+///
+/// Also `impl const Atom`, conditional on the field's own `Atom` impl
+/// being `const` (`where #field_ty: ~const Atom`) -- same feature-flag
+/// caveat as the enum derive above.
+fn derive_for_newtype(input: &DeriveInput, data: &DataStruct) -> TokenStream2 {
+    let name = &input.ident;
+    let fields: Vec<_> = data.fields.iter().collect();
+
+    let field = match fields.as_slice() {
+        [field] => field,
+        _ => {
+            return syn::Error::new_spanned(
+                &input.ident,
+                "`#[derive(Atom)]` on a struct requires exactly one field",
+            )
+            .to_compile_error();
+        }
+    };
+    let field_ty = &field.ty;
+    let access = match &field.ident {
+        Some(ident) => quote! { self.#ident },
+        None => quote! { self.0 },
+    };
+    let construct = match &field.ident {
+        Some(ident) => quote! { #name { #ident: inner } },
+        None => quote! { #name(inner) },
+    };
+
+    quote! {
+        impl const ::atomig::Atom for #name
+        where
+            #field_ty: ~const ::atomig::Atom,
+        {
+            type Impl = <#field_ty as ::atomig::Atom>::Impl;
+
+            fn pack(self) -> <Self::Impl as ::atomig::AtomicImpl>::Inner {
+                ::atomig::Atom::pack(#access)
+            }
+
+            fn unpack(src: <Self::Impl as ::atomig::AtomicImpl>::Inner) -> Self {
+                let inner = <#field_ty as ::atomig::Atom>::unpack(src);
+                #construct
+            }
+        }
+
+        // Forwarded only when the wrapped field is itself
+        // `IdentityPacked`, much as the `Atom` impl above is `const` only
+        // when the field's own `Atom` impl is: the wrapped field packs onto its
+        // `Impl` with no reinterpretation, and wrapping it in a
+        // single-field newtype doesn't change that, so `Atomic<#name>`'s
+        // numeric RMW methods (`fetch_add`/`fetch_sub`/`fetch_min`/
+        // `fetch_max`) stay available.
+        impl ::atomig::IdentityPacked for #name where #field_ty: ::atomig::IdentityPacked {}
+    }
+}
+
+/// Extracts the single identifier out of a `#[repr(uN)]` attribute.
+fn repr_ident(input: &DeriveInput) -> syn::Result<syn::Ident> {
+    let repr = input
+        .attrs
+        .iter()
+        .find(|attr| attr.path.is_ident("repr"))
+        .ok_or_else(|| {
+            syn::Error::new_spanned(
+                &input.ident,
+                "`#[derive(Atom)]` on an enum requires an explicit `#[repr(uN)]`",
+            )
+        })?;
+    repr.parse_args::<syn::Ident>()
+}
+
+/// Maps a `#[repr(..)]` integer identifier onto the matching
+/// `core::sync::atomic` atomic type.
+fn atomic_ident_for_repr(repr: &syn::Ident) -> syn::Result<syn::Ident> {
+    let ident = match repr.to_string().as_str() {
+        "u8" => format_ident!("AtomicU8"),
+        "i8" => format_ident!("AtomicI8"),
+        "u16" => format_ident!("AtomicU16"),
+        "i16" => format_ident!("AtomicI16"),
+        "u32" => format_ident!("AtomicU32"),
+        "i32" => format_ident!("AtomicI32"),
+        "u64" => format_ident!("AtomicU64"),
+        "i64" => format_ident!("AtomicI64"),
+        "usize" => format_ident!("AtomicUsize"),
+        "isize" => format_ident!("AtomicIsize"),
+        other => {
+            return Err(syn::Error::new_spanned(
+                repr,
+                format!("`#[derive(Atom)]` doesn't support `#[repr({})]`", other),
+            ));
+        }
+    };
+    Ok(ident)
+}
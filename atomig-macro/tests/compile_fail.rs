@@ -0,0 +1,9 @@
+//! Compile-fail coverage for the error paths `#[derive(Atom)]` emits nice
+//! messages for: unions, non-unit enum variants, enums missing
+//! `#[repr(uN)]`, and structs with more than one field.
+
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
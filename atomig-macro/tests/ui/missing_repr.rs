@@ -0,0 +1,9 @@
+use atomig_macro::Atom;
+
+#[derive(Atom)]
+enum Status {
+    Idle,
+    Busy,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use atomig_macro::Atom;
+
+#[derive(Atom)]
+struct Pair {
+    a: u32,
+    b: u32,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use atomig_macro::Atom;
+
+#[derive(Atom)]
+#[repr(u8)]
+enum Status {
+    Idle,
+    Busy(u8),
+}
+
+fn main() {}
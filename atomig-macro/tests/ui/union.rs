@@ -0,0 +1,8 @@
+use atomig_macro::Atom;
+
+#[derive(Atom)]
+union Bits {
+    n: u32,
+}
+
+fn main() {}
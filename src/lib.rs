@@ -1,22 +1,89 @@
 #![feature(cfg_target_has_atomic)]
+#![feature(const_trait_impl)]
+#![no_std]
 
-use std::sync::atomic::{
+// Nothing in this crate actually needs `std` -- it's built entirely on
+// `core::sync::atomic` -- but the `std` feature is kept as an opt-in for
+// downstream crates that want to enable it transitively (e.g. because
+// their own `no_std` support is likewise feature-gated).
+#[cfg(feature = "std")]
+extern crate std;
+
+// `#[derive(Atom)]`'s expansion refers to `::atomig::*` absolute paths, as
+// it must for downstream crates; this lets that same expansion resolve
+// when exercised against ourselves in this crate's own tests.
+#[cfg(all(test, feature = "derive"))]
+extern crate self as atomig;
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{
     self, Ordering,
 };
 
+/// Derives [`Atom`] for fieldless `#[repr(uN)]` enums and single-field
+/// newtype structs; see `atomig_macro` for what each shape expands to.
+#[cfg(feature = "derive")]
+pub use atomig_macro::Atom;
+
 
 /// Types that support atomic operations on the current platform.
-pub trait Atom {
+///
+/// Marked `const` so that types whose `pack`/`unpack` can run at compile
+/// time (via `impl const Atom for ...`) let [`Atomic::new`] be a `const
+/// fn`; see its docs.
+pub const trait Atom {
     type Impl: AtomicImpl;
     fn pack(self) -> <Self::Impl as AtomicImpl>::Inner;
     fn unpack(src: <Self::Impl as AtomicImpl>::Inner) -> Self;
 }
 
+/// Marker for `Atom` types whose `pack`/`unpack` is the identity function
+/// (generated via [`id_pack_unpack!`]), i.e. the packed bits *are* the
+/// value, with no reinterpretation in between.
+///
+/// This gates `Atomic<T>`'s numeric RMW methods (see `impl<T: Atom>
+/// Atomic<T> where T: IdentityPacked, T::Impl: AtomicIntegerImpl`):
+/// arithmetic on the packed integer only agrees with arithmetic on `T`
+/// itself for these types. The float atomics are the counterexample this
+/// exists to exclude -- `f32`/`f64` pack onto the *same* `AtomicU32`/
+/// `AtomicU64` that `u32`/`u64` do via `to_bits`/`from_bits`, so integer
+/// `fetch_add` on the packed bits would silently do the wrong thing; they
+/// get their own bit-pattern-correct `fetch_add`/`fetch_sub` instead.
+pub trait IdentityPacked: Atom {}
+
 pub struct Atomic<T: Atom>(T::Impl);
 
 impl<T: Atom> Atomic<T> {
-    pub fn new(v: T) -> Self {
-        Self(T::Impl::new(v.pack()))
+    /// Creates a new `Atomic<T>`, initialized to `v`.
+    ///
+    /// This is a `const fn` whenever `T`'s `Atom` impl and `T::Impl`'s
+    /// constructor are both `const` -- which holds for every type in this
+    /// crate built from `id_pack_unpack!` (`bool`, the integers, `*mut
+    /// T`), a `#[derive(Atom)]`'d enum, or a newtype wrapping one of the
+    /// above, since identity packing and the std atomic constructors are
+    /// both trivially `const`. It's the single most common way to
+    /// initialize a `static`:
+    ///
+    /// ```ignore
+    /// #![feature(const_trait_impl)]
+    ///
+    /// static COUNT: Atomic<u32> = Atomic::new(0);
+    /// ```
+    ///
+    /// `impl const Trait` is unstable, so this crate's own use of it
+    /// doesn't shield callers from the feature: any crate that wants a
+    /// `const fn new`/`static` for a type built on `~const Atom` (as
+    /// above) must add `#![feature(const_trait_impl)]` itself. Types
+    /// going through [`atom_via_lock!`] or a hand-written non-`const`
+    /// `Atom` impl don't need it -- `Atomic::new` for those just isn't a
+    /// `const fn`.
+    pub const fn new(v: T) -> Self
+    where
+        T: ~const Atom,
+        T::Impl: ~const ConstNewAtomicImpl,
+    {
+        Self(<T::Impl as ConstNewAtomicImpl>::const_new(v.pack()))
     }
 
     // fn get_mut(&mut self) -> &mut Self::Inner;
@@ -30,6 +97,36 @@ impl<T: Atom> Atomic<T> {
     pub fn store(&self, v: T, order: Ordering) {
         self.0.store(v.pack(), order);
     }
+}
+
+impl<T: Atom + Default> Default for Atomic<T>
+where
+    T::Impl: ConstNewAtomicImpl,
+{
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: Atom> From<T> for Atomic<T>
+where
+    T::Impl: ConstNewAtomicImpl,
+{
+    fn from(v: T) -> Self {
+        Self::new(v)
+    }
+}
+
+impl<T: Atom + fmt::Debug> fmt::Debug for Atomic<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Atomic").field(&self.load(Ordering::SeqCst)).finish()
+    }
+}
+
+impl<T: Atom> Atomic<T>
+where
+    T::Impl: AtomicCasImpl,
+{
     pub fn swap(&self, v: T, order: Ordering) -> T {
         T::unpack(self.0.swap(v.pack(), order))
     }
@@ -63,6 +160,40 @@ impl<T: Atom> Atomic<T> {
     }
 }
 
+impl<T: Atom + Copy> Atomic<T>
+where
+    T::Impl: AtomicCasImpl,
+{
+    /// Fetches the current value, calls `f` with it, and attempts to store
+    /// the result back (retrying on spurious/contended failure) if `f`
+    /// returned `Some`. Returns `Ok` with the previous value on a
+    /// successful write, or `Err` with the current value if `f` returned
+    /// `None` (no write is attempted in that case).
+    ///
+    /// This is the general-purpose building block the other RMW methods
+    /// above can all be expressed in terms of, and lets callers implement
+    /// e.g. saturating counters or tagged-pointer transitions on any `Atom`
+    /// type without hand-rolling the compare-exchange loop.
+    pub fn fetch_update<F>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        mut f: F,
+    ) -> Result<T, T>
+    where
+        F: FnMut(T) -> Option<T>,
+    {
+        let mut current = self.load(fetch_order);
+        while let Some(new) = f(current) {
+            match self.compare_exchange_weak(current, new, set_order, fetch_order) {
+                Ok(prev) => return Ok(prev),
+                Err(prev) => current = prev,
+            }
+        }
+        Err(current)
+    }
+}
+
 impl<T: Atom> Atomic<T>
 where
     T::Impl: AtomicLogicImpl,
@@ -81,8 +212,33 @@ where
     }
 }
 
+impl<T: Atom + IdentityPacked> Atomic<T>
+where
+    T::Impl: AtomicIntegerImpl,
+{
+    pub fn fetch_add(&self, val: T, order: Ordering) -> T {
+        T::unpack(self.0.fetch_add(val.pack(), order))
+    }
+    pub fn fetch_sub(&self, val: T, order: Ordering) -> T {
+        T::unpack(self.0.fetch_sub(val.pack(), order))
+    }
+    pub fn fetch_min(&self, val: T, order: Ordering) -> T {
+        T::unpack(self.0.fetch_min(val.pack(), order))
+    }
+    pub fn fetch_max(&self, val: T, order: Ordering) -> T {
+        T::unpack(self.0.fetch_max(val.pack(), order))
+    }
+}
+
 
 
+/// Types that provide atomic load/store on the current platform.
+///
+/// This is deliberately kept separate from [`AtomicCasImpl`]: some targets
+/// (certain ARMv6-M and RISC-V configurations) only provide atomic
+/// load/store instructions and no compare-and-swap, mirroring the
+/// `target_has_atomic_load_store` (this trait) / bare `target_has_atomic`
+/// (implies CAS, see [`AtomicCasImpl`]) split in libcore.
 pub trait AtomicImpl {
     type Inner;
 
@@ -91,6 +247,28 @@ pub trait AtomicImpl {
     fn into_inner(self) -> Self::Inner;
     fn load(&self, order: Ordering) -> Self::Inner;
     fn store(&self, v: Self::Inner, order: Ordering);
+}
+
+/// Re-exposes [`AtomicImpl::new`] as a `const fn`, so that [`Atomic::new`]
+/// can be `const` too. Split out from `AtomicImpl` itself rather than just
+/// marking that trait `const`, since `load`/`store`/etc. on the std
+/// atomics fundamentally can't run at compile time -- only construction
+/// can.
+///
+/// Implemented for every [`AtomicImpl`] backend in this crate (the std
+/// atomics, [`Locked`], and the `critical-section` backend): all of them
+/// bottom out in either a std atomic constructor or `UnsafeCell::new`,
+/// both already `const fn`.
+pub const trait ConstNewAtomicImpl: AtomicImpl {
+    fn const_new(v: Self::Inner) -> Self;
+}
+
+/// Types that additionally provide atomic compare-and-swap RMW operations.
+///
+/// Only implemented where the bare `target_has_atomic = "N"` cfg holds for
+/// the relevant width -- in libcore that already implies CAS support, unlike
+/// `target_has_atomic_load_store = "N"` which just covers [`AtomicImpl`].
+pub trait AtomicCasImpl: AtomicImpl {
     fn swap(&self, v: Self::Inner, order: Ordering) -> Self::Inner;
 
     fn compare_and_swap(
@@ -124,6 +302,20 @@ pub trait AtomicLogicImpl: AtomicImpl {
     fn fetch_xor(&self, val: Self::Inner, order: Ordering) -> Self::Inner;
 }
 
+/// The numeric counterpart to [`AtomicLogicImpl`]: atomic arithmetic RMW,
+/// implemented for the std integer atomics. Types that pack onto one of
+/// those but whose arithmetic isn't the integer's arithmetic (e.g. the
+/// float atomics, which use `AtomicU32`/`AtomicU64`) don't implement
+/// [`IdentityPacked`], so `Atomic<T>` doesn't expose these for them; they
+/// instead build `fetch_add`/`fetch_sub` out of a compare-exchange loop;
+/// see `Atomic<f32>` and `Atomic<f64>`.
+pub trait AtomicIntegerImpl: AtomicImpl {
+    fn fetch_add(&self, val: Self::Inner, order: Ordering) -> Self::Inner;
+    fn fetch_sub(&self, val: Self::Inner, order: Ordering) -> Self::Inner;
+    fn fetch_min(&self, val: Self::Inner, order: Ordering) -> Self::Inner;
+    fn fetch_max(&self, val: Self::Inner, order: Ordering) -> Self::Inner;
+}
+
 // ===============================================================================================
 // ===== Implementations for standard library types
 // ===============================================================================================
@@ -162,6 +354,11 @@ macro_rules! pass_through_methods {
         fn store(&self, v: Self::Inner, order: Ordering) {
             self.store(v, order)
         }
+    };
+}
+
+macro_rules! cas_pass_through_methods {
+    () => {
         #[inline(always)]
         fn swap(&self, v: Self::Inner, order: Ordering) -> Self::Inner {
             self.swap(v, order)
@@ -215,43 +412,820 @@ macro_rules! logical_pass_through_methods {
     };
 }
 
-#[cfg(target_has_atomic = "ptr")]
-impl<T> Atom for *mut T {
+macro_rules! numeric_pass_through_methods {
+    () => {
+        fn fetch_add(&self, val: Self::Inner, order: Ordering) -> Self::Inner {
+            self.fetch_add(val, order)
+        }
+        fn fetch_sub(&self, val: Self::Inner, order: Ordering) -> Self::Inner {
+            self.fetch_sub(val, order)
+        }
+        fn fetch_min(&self, val: Self::Inner, order: Ordering) -> Self::Inner {
+            self.fetch_min(val, order)
+        }
+        fn fetch_max(&self, val: Self::Inner, order: Ordering) -> Self::Inner {
+            self.fetch_max(val, order)
+        }
+    };
+}
+
+macro_rules! const_new_pass_through {
+    ($ty:ty) => {
+        #[inline(always)]
+        fn const_new(v: Self::Inner) -> Self {
+            <$ty>::new(v)
+        }
+    };
+}
+
+#[cfg(target_has_atomic_load_store = "ptr")]
+impl<T> const Atom for *mut T {
     type Impl = atomic::AtomicPtr<T>;
     id_pack_unpack!();
 }
 
-#[cfg(target_has_atomic = "ptr")]
+#[cfg(target_has_atomic_load_store = "ptr")]
+impl<T> IdentityPacked for *mut T {}
+
+#[cfg(target_has_atomic_load_store = "ptr")]
 impl<T> AtomicImpl for atomic::AtomicPtr<T> {
     type Inner = *mut T;
     pass_through_methods!(atomic::AtomicPtr<T>);
 }
 
+#[cfg(target_has_atomic_load_store = "ptr")]
+impl<T> const ConstNewAtomicImpl for atomic::AtomicPtr<T> {
+    const_new_pass_through!(atomic::AtomicPtr<T>);
+}
+
+// Bare `target_has_atomic = "ptr"` (unlike the `target_has_atomic_load_store`
+// used above) already implies CAS support in libcore's cfg model.
+#[cfg(target_has_atomic = "ptr")]
+impl<T> AtomicCasImpl for atomic::AtomicPtr<T> {
+    cas_pass_through_methods!();
+}
+
 
 macro_rules! impl_std_atomics {
-    ($ty:ty, $impl_ty:ident) => {
-        impl Atom for $ty {
+    ($ty:ty, $impl_ty:ident, $width:literal) => {
+        impl const Atom for $ty {
             type Impl = atomic::$impl_ty;
             id_pack_unpack!();
         }
 
+        impl IdentityPacked for $ty {}
+
         impl AtomicImpl for atomic::$impl_ty {
             type Inner = $ty;
             pass_through_methods!(atomic::$impl_ty);
         }
 
+        impl const ConstNewAtomicImpl for atomic::$impl_ty {
+            const_new_pass_through!(atomic::$impl_ty);
+        }
+
+        // Bare `target_has_atomic = $width` (unlike the
+        // `target_has_atomic_load_store` this macro is invoked under)
+        // already implies CAS support in libcore's cfg model.
+        #[cfg(target_has_atomic = $width)]
+        impl AtomicCasImpl for atomic::$impl_ty {
+            cas_pass_through_methods!();
+        }
+
+        // `fetch_and`/`fetch_nand`/`fetch_or`/`fetch_xor` are RMW ops too,
+        // so they need the same full-capability cfg as `AtomicCasImpl`
+        // above, not just load/store.
+        #[cfg(target_has_atomic = $width)]
         impl AtomicLogicImpl for atomic::$impl_ty {
             logical_pass_through_methods!();
         }
     };
 }
 
-#[cfg(target_has_atomic = "8")] impl_std_atomics!(bool, AtomicBool);
-#[cfg(target_has_atomic = "8")] impl_std_atomics!(u8, AtomicU8);
-#[cfg(target_has_atomic = "8")] impl_std_atomics!(i8, AtomicI8);
-#[cfg(target_has_atomic = "16")] impl_std_atomics!(u16, AtomicU16);
-#[cfg(target_has_atomic = "16")] impl_std_atomics!(i16, AtomicI16);
-#[cfg(target_has_atomic = "32")] impl_std_atomics!(u32, AtomicU32);
-#[cfg(target_has_atomic = "32")] impl_std_atomics!(i32, AtomicI32);
-#[cfg(target_has_atomic = "64")] impl_std_atomics!(u64, AtomicU64);
-#[cfg(target_has_atomic = "64")] impl_std_atomics!(i64, AtomicI64);
+#[cfg(target_has_atomic_load_store = "8")] impl_std_atomics!(bool, AtomicBool, "8");
+#[cfg(target_has_atomic_load_store = "8")] impl_std_atomics!(u8, AtomicU8, "8");
+#[cfg(target_has_atomic_load_store = "8")] impl_std_atomics!(i8, AtomicI8, "8");
+#[cfg(target_has_atomic_load_store = "16")] impl_std_atomics!(u16, AtomicU16, "16");
+#[cfg(target_has_atomic_load_store = "16")] impl_std_atomics!(i16, AtomicI16, "16");
+#[cfg(target_has_atomic_load_store = "32")] impl_std_atomics!(u32, AtomicU32, "32");
+#[cfg(target_has_atomic_load_store = "32")] impl_std_atomics!(i32, AtomicI32, "32");
+#[cfg(target_has_atomic_load_store = "64")] impl_std_atomics!(u64, AtomicU64, "64");
+#[cfg(target_has_atomic_load_store = "64")] impl_std_atomics!(i64, AtomicI64, "64");
+
+macro_rules! impl_numeric_atomics {
+    ($ty:ty, $impl_ty:ident) => {
+        impl AtomicIntegerImpl for atomic::$impl_ty {
+            numeric_pass_through_methods!();
+        }
+    };
+}
+
+// Unlike `impl_std_atomics!`, this isn't applied to `bool`: `AtomicBool`
+// has no `fetch_add`/`fetch_sub`/`fetch_min`/`fetch_max`, only the bitwise
+// ops already covered by `AtomicLogicImpl`.
+#[cfg(target_has_atomic = "8")] impl_numeric_atomics!(u8, AtomicU8);
+#[cfg(target_has_atomic = "8")] impl_numeric_atomics!(i8, AtomicI8);
+#[cfg(target_has_atomic = "16")] impl_numeric_atomics!(u16, AtomicU16);
+#[cfg(target_has_atomic = "16")] impl_numeric_atomics!(i16, AtomicI16);
+#[cfg(target_has_atomic = "32")] impl_numeric_atomics!(u32, AtomicU32);
+#[cfg(target_has_atomic = "32")] impl_numeric_atomics!(i32, AtomicI32);
+#[cfg(target_has_atomic = "64")] impl_numeric_atomics!(u64, AtomicU64);
+#[cfg(target_has_atomic = "64")] impl_numeric_atomics!(i64, AtomicI64);
+
+// ===============================================================================================
+// ===== Float atomics, packed onto the equivalently-sized integer atomic
+// ===============================================================================================
+
+/// `f32`/`f64` have no native atomic instructions of their own, but they're
+/// the same size as `u32`/`u64`, so they ride on those atomics via
+/// `to_bits`/`from_bits` through the same `pack`/`unpack` machinery used
+/// everywhere else in this crate.
+///
+/// Note that `compare_exchange`/`compare_and_swap` therefore compare
+/// *bit patterns*, not IEEE 754 values: `-0.0` and `+0.0` have distinct bit
+/// patterns and compare unequal, and a `NaN` payload is compared exactly
+/// rather than via the (always-false) IEEE `NaN != NaN` rule.
+#[cfg(target_has_atomic_load_store = "32")]
+impl Atom for f32 {
+    type Impl = atomic::AtomicU32;
+    fn pack(self) -> u32 {
+        self.to_bits()
+    }
+    fn unpack(src: u32) -> Self {
+        f32::from_bits(src)
+    }
+}
+
+#[cfg(target_has_atomic_load_store = "64")]
+impl Atom for f64 {
+    type Impl = atomic::AtomicU64;
+    fn pack(self) -> u64 {
+        self.to_bits()
+    }
+    fn unpack(src: u64) -> Self {
+        f64::from_bits(src)
+    }
+}
+
+// `f32`/`f64` pack onto `u32`/`u64`, but float addition isn't integer
+// addition, so they can't use `AtomicIntegerImpl`/`numeric_pass_through_methods!`
+// like the real integer atomics do. Instead `fetch_add`/`fetch_sub` are
+// built directly out of a `compare_exchange_weak` loop, the same technique
+// `integer-atomics` uses to build wider RMW ops on top of a narrower CAS --
+// which is also why these need the bare `target_has_atomic` cfg (CAS),
+// not just the `target_has_atomic_load_store` the `Atom` impls above need.
+macro_rules! float_fetch_add_sub {
+    ($ty:ty) => {
+        pub fn fetch_add(&self, val: $ty, order: Ordering) -> $ty {
+            let mut current = self.load(Ordering::Relaxed);
+            loop {
+                let new = current + val;
+                match self.compare_exchange_weak(current, new, order, Ordering::Relaxed) {
+                    Ok(prev) => return prev,
+                    Err(prev) => current = prev,
+                }
+            }
+        }
+
+        pub fn fetch_sub(&self, val: $ty, order: Ordering) -> $ty {
+            self.fetch_add(-val, order)
+        }
+    };
+}
+
+#[cfg(target_has_atomic = "32")]
+impl Atomic<f32> {
+    float_fetch_add_sub!(f32);
+}
+
+#[cfg(target_has_atomic = "64")]
+impl Atomic<f64> {
+    float_fetch_add_sub!(f64);
+}
+
+// ===============================================================================================
+// ===== Lock-based fallback for arbitrary `Copy` types
+// ===============================================================================================
+
+/// Number of sharded spinlocks backing [`Locked<T>`]. A small, fixed table
+/// keeps the fallback's static footprint bounded while still spreading
+/// contention across unrelated `Atomic<T>`s.
+const LOCK_SHARDS: usize = 64;
+
+/// A simple spinlock built on `AtomicBool`, used to guard the shards in
+/// [`LOCKS`]. This crate already depends on `AtomicBool` via
+/// [`impl_std_atomics!`], so reusing it here avoids pulling in a second
+/// synchronization primitive.
+struct SpinLock(atomic::AtomicBool);
+
+impl SpinLock {
+    const fn new() -> Self {
+        Self(atomic::AtomicBool::new(false))
+    }
+
+    fn lock(&self) {
+        while self.0.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            while self.0.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    fn unlock(&self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+// `[SpinLock::new(); LOCK_SHARDS]` would require `SpinLock: Copy`, which it
+// isn't (it wraps an `AtomicBool`), so the table is spelled out by hand.
+#[rustfmt::skip]
+static LOCKS: [SpinLock; LOCK_SHARDS] = [
+    SpinLock::new(), SpinLock::new(), SpinLock::new(), SpinLock::new(),
+    SpinLock::new(), SpinLock::new(), SpinLock::new(), SpinLock::new(),
+    SpinLock::new(), SpinLock::new(), SpinLock::new(), SpinLock::new(),
+    SpinLock::new(), SpinLock::new(), SpinLock::new(), SpinLock::new(),
+    SpinLock::new(), SpinLock::new(), SpinLock::new(), SpinLock::new(),
+    SpinLock::new(), SpinLock::new(), SpinLock::new(), SpinLock::new(),
+    SpinLock::new(), SpinLock::new(), SpinLock::new(), SpinLock::new(),
+    SpinLock::new(), SpinLock::new(), SpinLock::new(), SpinLock::new(),
+    SpinLock::new(), SpinLock::new(), SpinLock::new(), SpinLock::new(),
+    SpinLock::new(), SpinLock::new(), SpinLock::new(), SpinLock::new(),
+    SpinLock::new(), SpinLock::new(), SpinLock::new(), SpinLock::new(),
+    SpinLock::new(), SpinLock::new(), SpinLock::new(), SpinLock::new(),
+    SpinLock::new(), SpinLock::new(), SpinLock::new(), SpinLock::new(),
+    SpinLock::new(), SpinLock::new(), SpinLock::new(), SpinLock::new(),
+    SpinLock::new(), SpinLock::new(), SpinLock::new(), SpinLock::new(),
+    SpinLock::new(), SpinLock::new(), SpinLock::new(), SpinLock::new(),
+];
+
+/// Picks the shard guarding `ptr`'s address, spreading nearby addresses
+/// across the table via a cheap multiplicative hash.
+fn lock_shard<T>(ptr: *const T) -> &'static SpinLock {
+    let addr = ptr as usize;
+    let hash = (addr >> 4).wrapping_mul(0x9E37_79B1);
+    &LOCKS[hash % LOCK_SHARDS]
+}
+
+/// Fallback [`AtomicImpl`]/[`AtomicCasImpl`] for any `T: Copy` that has no
+/// matching native atomic instruction, backed by [`LOCKS`].
+///
+/// Every operation locks the shard guarding this value's address, performs
+/// a plain memory operation on the `T` held inline in its `UnsafeCell`, and unlocks. Because the lock
+/// is always taken, every operation provides at least `Acquire`/`Release`
+/// semantics, regardless of the requested [`Ordering`] -- the ordering
+/// argument is accepted for API compatibility but has no finer-grained
+/// effect than "the lock is held".
+///
+/// This mirrors the approach the `atomic` crate uses for types with no
+/// native atomic of the right width: unlike the std-backed [`AtomicImpl`]s
+/// above, `Locked<T>` works for *any* `T: Copy`, including
+/// `#[derive(Clone, Copy)]` structs and enums, at the cost of a lock
+/// instead of a lock-free instruction. Prefer a native `Atom` impl (an
+/// integer, `bool`, `*mut T`, or one that packs onto one of those) whenever
+/// the packed size matches a real atomic type; reach for `Locked<T>` only
+/// when it doesn't.
+pub struct Locked<T>(UnsafeCell<T>);
+
+unsafe impl<T: Send> Send for Locked<T> {}
+unsafe impl<T: Send> Sync for Locked<T> {}
+
+impl<T: Copy> AtomicImpl for Locked<T> {
+    type Inner = T;
+
+    fn new(v: T) -> Self {
+        Self(UnsafeCell::new(v))
+    }
+    fn get_mut(&mut self) -> &mut T {
+        self.0.get_mut()
+    }
+    fn into_inner(self) -> T {
+        self.0.into_inner()
+    }
+    fn load(&self, _order: Ordering) -> T {
+        let shard = lock_shard(self.0.get());
+        shard.lock();
+        let v = unsafe { *self.0.get() };
+        shard.unlock();
+        v
+    }
+    fn store(&self, v: T, _order: Ordering) {
+        let shard = lock_shard(self.0.get());
+        shard.lock();
+        unsafe { *self.0.get() = v };
+        shard.unlock();
+    }
+}
+
+impl<T: Copy> const ConstNewAtomicImpl for Locked<T> {
+    fn const_new(v: T) -> Self {
+        Self(UnsafeCell::new(v))
+    }
+}
+
+impl<T: Copy + PartialEq> AtomicCasImpl for Locked<T> {
+    fn swap(&self, v: T, _order: Ordering) -> T {
+        let shard = lock_shard(self.0.get());
+        shard.lock();
+        let old = unsafe { *self.0.get() };
+        unsafe { *self.0.get() = v };
+        shard.unlock();
+        old
+    }
+
+    fn compare_and_swap(&self, current: T, new: T, order: Ordering) -> T {
+        match self.compare_exchange(current, new, order, order) {
+            Ok(v) | Err(v) => v,
+        }
+    }
+
+    fn compare_exchange(
+        &self,
+        current: T,
+        new: T,
+        _success: Ordering,
+        _failure: Ordering,
+    ) -> Result<T, T> {
+        let shard = lock_shard(self.0.get());
+        shard.lock();
+        let old = unsafe { *self.0.get() };
+        let result = if old == current {
+            unsafe { *self.0.get() = new };
+            Ok(old)
+        } else {
+            Err(old)
+        };
+        shard.unlock();
+        result
+    }
+
+    fn compare_exchange_weak(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T> {
+        self.compare_exchange(current, new, success, failure)
+    }
+}
+
+/// Opts an arbitrary `#[derive(Clone, Copy)]` struct or enum into
+/// `Atomic<T>` via the [`Locked`] fallback, storing `Self` directly rather
+/// than packing onto an integer.
+///
+/// This is the "blanket route" for types with no native atomic of the
+/// matching width: unlike [`id_pack_unpack!`], which reuses an existing
+/// native `Impl`, this macro always selects [`Locked<Self>`] and therefore
+/// always goes through the lock table, even if a future native atomic of
+/// the right size would apply. Prefer a hand-written `Atom` impl against a
+/// native `Impl` when one exists for your type's size.
+///
+/// Generates a plain (non-`const`) `impl Atom`, not `impl const Atom`:
+/// `impl const Trait` is unstable syntax, so emitting it here would force
+/// every crate invoking this macro to also add
+/// `#![feature(const_trait_impl)]` just to call it, even though arbitrary
+/// `Copy` types going through the lock table are not the `const fn
+/// new`/`static` use case `ConstNewAtomicImpl` exists for in the first
+/// place. Implement `Atom` by hand against a `~const`-bounded `Impl` if
+/// you need `Atomic::<Self>::new` to be `const`.
+#[macro_export]
+macro_rules! atom_via_lock {
+    ($ty:ty) => {
+        impl $crate::Atom for $ty {
+            type Impl = $crate::Locked<$ty>;
+            fn pack(self) -> <Self::Impl as $crate::AtomicImpl>::Inner {
+                self
+            }
+            fn unpack(src: <Self::Impl as $crate::AtomicImpl>::Inner) -> Self {
+                src
+            }
+        }
+    };
+}
+
+// ===============================================================================================
+// ===== `critical-section`-based emulation backend (feature = "critical-section")
+// ===============================================================================================
+
+/// Emulates atomic operations on targets with no atomic instructions at
+/// all by performing a plain read-modify-write inside a
+/// [`critical_section::with`], i.e. with interrupts (or whatever the
+/// target's `critical-section` implementation disables) turned off for
+/// the duration -- the same technique `atomic-polyfill` uses.
+///
+/// Selecting this backend is a compile-time choice made by the caller:
+/// prefer a real hardware [`AtomicImpl`] (the std atomics above) or the
+/// [`Locked<T>`] spinlock table (itself built on a hardware compare-and-swap
+/// on `AtomicBool`) wherever the target supports one, and reach for
+/// `CriticalSection<Inner>` only for the widths it lacks, e.g. `u64` on a
+/// 32-bit microcontroller with no native 64-bit atomics.
+#[cfg(feature = "critical-section")]
+pub struct CriticalSection<Inner>(UnsafeCell<Inner>);
+
+#[cfg(feature = "critical-section")]
+unsafe impl<Inner: Send> Send for CriticalSection<Inner> {}
+#[cfg(feature = "critical-section")]
+unsafe impl<Inner: Send> Sync for CriticalSection<Inner> {}
+
+#[cfg(feature = "critical-section")]
+impl<Inner: Copy> AtomicImpl for CriticalSection<Inner> {
+    type Inner = Inner;
+
+    fn new(v: Inner) -> Self {
+        Self(UnsafeCell::new(v))
+    }
+    fn get_mut(&mut self) -> &mut Inner {
+        self.0.get_mut()
+    }
+    fn into_inner(self) -> Inner {
+        self.0.into_inner()
+    }
+    fn load(&self, _order: Ordering) -> Inner {
+        critical_section::with(|_| unsafe { *self.0.get() })
+    }
+    fn store(&self, v: Inner, _order: Ordering) {
+        critical_section::with(|_| unsafe { *self.0.get() = v });
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl<Inner: Copy> const ConstNewAtomicImpl for CriticalSection<Inner> {
+    fn const_new(v: Inner) -> Self {
+        Self(UnsafeCell::new(v))
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl<Inner: Copy + PartialEq> AtomicCasImpl for CriticalSection<Inner> {
+    fn swap(&self, v: Inner, _order: Ordering) -> Inner {
+        critical_section::with(|_| unsafe {
+            let old = *self.0.get();
+            *self.0.get() = v;
+            old
+        })
+    }
+
+    fn compare_and_swap(&self, current: Inner, new: Inner, order: Ordering) -> Inner {
+        match self.compare_exchange(current, new, order, order) {
+            Ok(v) | Err(v) => v,
+        }
+    }
+
+    fn compare_exchange(
+        &self,
+        current: Inner,
+        new: Inner,
+        _success: Ordering,
+        _failure: Ordering,
+    ) -> Result<Inner, Inner> {
+        critical_section::with(|_| unsafe {
+            let old = *self.0.get();
+            if old == current {
+                *self.0.get() = new;
+                Ok(old)
+            } else {
+                Err(old)
+            }
+        })
+    }
+
+    fn compare_exchange_weak(
+        &self,
+        current: Inner,
+        new: Inner,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<Inner, Inner> {
+        self.compare_exchange(current, new, success, failure)
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl<Inner> AtomicLogicImpl for CriticalSection<Inner>
+where
+    Inner: Copy
+        + core::ops::BitAnd<Output = Inner>
+        + core::ops::BitOr<Output = Inner>
+        + core::ops::BitXor<Output = Inner>
+        + core::ops::Not<Output = Inner>,
+{
+    fn fetch_and(&self, val: Inner, _order: Ordering) -> Inner {
+        critical_section::with(|_| unsafe {
+            let old = *self.0.get();
+            *self.0.get() = old & val;
+            old
+        })
+    }
+    fn fetch_nand(&self, val: Inner, _order: Ordering) -> Inner {
+        critical_section::with(|_| unsafe {
+            let old = *self.0.get();
+            *self.0.get() = !(old & val);
+            old
+        })
+    }
+    fn fetch_or(&self, val: Inner, _order: Ordering) -> Inner {
+        critical_section::with(|_| unsafe {
+            let old = *self.0.get();
+            *self.0.get() = old | val;
+            old
+        })
+    }
+    fn fetch_xor(&self, val: Inner, _order: Ordering) -> Inner {
+        critical_section::with(|_| unsafe {
+            let old = *self.0.get();
+            *self.0.get() = old ^ val;
+            old
+        })
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl<Inner> AtomicIntegerImpl for CriticalSection<Inner>
+where
+    Inner: Copy + Ord + core::ops::Add<Output = Inner> + core::ops::Sub<Output = Inner>,
+{
+    fn fetch_add(&self, val: Inner, _order: Ordering) -> Inner {
+        critical_section::with(|_| unsafe {
+            let old = *self.0.get();
+            *self.0.get() = old + val;
+            old
+        })
+    }
+    fn fetch_sub(&self, val: Inner, _order: Ordering) -> Inner {
+        critical_section::with(|_| unsafe {
+            let old = *self.0.get();
+            *self.0.get() = old - val;
+            old
+        })
+    }
+    fn fetch_min(&self, val: Inner, _order: Ordering) -> Inner {
+        critical_section::with(|_| unsafe {
+            let old = *self.0.get();
+            *self.0.get() = old.min(val);
+            old
+        })
+    }
+    fn fetch_max(&self, val: Inner, _order: Ordering) -> Inner {
+        critical_section::with(|_| unsafe {
+            let old = *self.0.get();
+            *self.0.get() = old.max(val);
+            old
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Width-gated like the `AtomicCasImpl` impls themselves: on a target
+    // with no CAS this wouldn't even compile, which is exactly the bug a
+    // bogus `target_has_atomic = "cas"` cfg used to hide.
+    #[cfg(target_has_atomic = "32")]
+    #[test]
+    fn compare_exchange_on_std_atomic() {
+        let a = Atomic::new(1u32);
+        assert_eq!(a.compare_exchange(1, 2, Ordering::SeqCst, Ordering::SeqCst), Ok(1));
+        assert_eq!(a.compare_exchange(1, 3, Ordering::SeqCst, Ordering::SeqCst), Err(2));
+        assert_eq!(a.load(Ordering::SeqCst), 2);
+        assert_eq!(a.swap(5, Ordering::SeqCst), 2);
+        assert_eq!(a.load(Ordering::SeqCst), 5);
+    }
+
+    // `Atom for f32`/`f64` compares bit patterns, not IEEE 754 values (see
+    // that impl's docs): `-0.0 == 0.0` but they have distinct bit patterns,
+    // while `NaN != NaN` but comparing a `NaN` against the exact bits
+    // already stored succeeds.
+    #[cfg(target_has_atomic = "32")]
+    #[test]
+    fn float_compare_exchange_compares_bit_patterns() {
+        let a = Atomic::new(0.0f32);
+        assert_eq!(a.compare_exchange(-0.0, 1.0, Ordering::SeqCst, Ordering::SeqCst), Err(0.0));
+
+        let b = Atomic::new(f32::NAN);
+        assert!(b.compare_exchange(f32::NAN, 1.0, Ordering::SeqCst, Ordering::SeqCst).is_ok());
+        assert_eq!(b.load(Ordering::SeqCst), 1.0);
+    }
+
+    // Exercises the `Locked<T>` fallback end to end: `Point` has no native
+    // atomic of matching width (two `i32`s), so `atom_via_lock!` is the
+    // only route onto `Atomic<T>`. This is the only coverage of the
+    // hand-rolled spinlock itself -- load/store/swap/compare_exchange all
+    // have to agree with each other through the lock for this to pass.
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    atom_via_lock!(Point);
+
+    // Numeric RMW on a plain integer atomic -- the pass-through methods
+    // `numeric_pass_through_methods!` generates straight onto the std
+    // atomic's own `fetch_add`/`fetch_sub`/`fetch_min`/`fetch_max`.
+    #[cfg(target_has_atomic = "32")]
+    #[test]
+    fn integer_fetch_add_sub_min_max() {
+        let a = Atomic::new(10i32);
+        assert_eq!(a.fetch_add(5, Ordering::SeqCst), 10);
+        assert_eq!(a.load(Ordering::SeqCst), 15);
+        assert_eq!(a.fetch_sub(4, Ordering::SeqCst), 15);
+        assert_eq!(a.load(Ordering::SeqCst), 11);
+        assert_eq!(a.fetch_min(3, Ordering::SeqCst), 11);
+        assert_eq!(a.load(Ordering::SeqCst), 3);
+        assert_eq!(a.fetch_max(20, Ordering::SeqCst), 3);
+        assert_eq!(a.load(Ordering::SeqCst), 20);
+    }
+
+    // `fetch_add`/`fetch_sub` on floats go through the `compare_exchange_weak`
+    // loop in `float_fetch_add_sub!`, not `AtomicIntegerImpl` -- this is the
+    // coverage `float_compare_exchange_compares_bit_patterns` above doesn't
+    // provide, since that test never calls either method.
+    #[cfg(target_has_atomic = "32")]
+    #[test]
+    fn float_fetch_add_sub_via_compare_exchange_loop() {
+        let a = Atomic::new(1.5f32);
+        assert_eq!(a.fetch_add(2.5, Ordering::SeqCst), 1.5);
+        assert_eq!(a.load(Ordering::SeqCst), 4.0);
+        assert_eq!(a.fetch_sub(1.0, Ordering::SeqCst), 4.0);
+        assert_eq!(a.load(Ordering::SeqCst), 3.0);
+    }
+
+    // `fetch_update` is the general-purpose compare-exchange-loop building
+    // block every other RMW method could be expressed in terms of; cover
+    // both the successful-write path and the `None`-returning no-op path.
+    #[cfg(target_has_atomic = "32")]
+    #[test]
+    fn fetch_update_writes_on_some_and_leaves_value_on_none() {
+        let a = Atomic::new(1i32);
+
+        let doubled = a.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |v| Some(v * 2));
+        assert_eq!(doubled, Ok(1));
+        assert_eq!(a.load(Ordering::SeqCst), 2);
+
+        let unchanged = a.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |_| None);
+        assert_eq!(unchanged, Err(2));
+        assert_eq!(a.load(Ordering::SeqCst), 2);
+    }
+
+    // Exercises the `critical-section` backend: no macro picks
+    // `CriticalSection<Inner>` automatically (unlike `atom_via_lock!` for
+    // `Locked<T>`), so a hand-written `Atom` impl is the normal way to wire
+    // a type onto it, same as a downstream crate targeting a no-CAS
+    // microcontroller would. Needs a real `critical_section::Impl`
+    // registered to run at all, which the `std` feature of the
+    // `critical-section` crate itself provides for this test binary.
+    #[cfg(feature = "critical-section")]
+    mod critical_section_backend {
+        use super::*;
+
+        struct Counter(u32);
+
+        impl Atom for Counter {
+            type Impl = CriticalSection<u32>;
+            fn pack(self) -> u32 {
+                self.0
+            }
+            fn unpack(src: u32) -> Self {
+                Counter(src)
+            }
+        }
+        impl IdentityPacked for Counter {}
+
+        #[test]
+        fn load_store_swap_compare_exchange_fetch_add() {
+            let a = Atomic::new(Counter(1));
+            assert_eq!(a.load(Ordering::SeqCst).0, 1);
+
+            a.store(Counter(2), Ordering::SeqCst);
+            assert_eq!(a.load(Ordering::SeqCst).0, 2);
+
+            assert_eq!(a.swap(Counter(3), Ordering::SeqCst).0, 2);
+
+            assert_eq!(
+                a.compare_exchange(Counter(3), Counter(4), Ordering::SeqCst, Ordering::SeqCst)
+                    .map(|v| v.0).map_err(|v| v.0),
+                Ok(3),
+            );
+            assert_eq!(
+                a.compare_exchange(Counter(3), Counter(5), Ordering::SeqCst, Ordering::SeqCst)
+                    .map(|v| v.0).map_err(|v| v.0),
+                Err(4),
+            );
+
+            assert_eq!(a.fetch_add(Counter(10), Ordering::SeqCst).0, 4);
+            assert_eq!(a.load(Ordering::SeqCst).0, 14);
+        }
+    }
+
+    // `static COUNT: Atomic<u32> = Atomic::new(0);` is the motivating use
+    // case for `Atomic::new` being a `const fn` in the first place (see its
+    // docs); make sure it actually works as a `static`, not just as a
+    // same-function local.
+    static COUNT: Atomic<u32> = Atomic::new(0);
+
+    #[cfg(target_has_atomic = "32")]
+    #[test]
+    fn const_new_static() {
+        assert_eq!(COUNT.load(Ordering::SeqCst), 0);
+        COUNT.store(1, Ordering::SeqCst);
+        assert_eq!(COUNT.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(target_has_atomic = "32")]
+    #[test]
+    fn default_and_from() {
+        let a: Atomic<u32> = Default::default();
+        assert_eq!(a.load(Ordering::SeqCst), 0);
+
+        let b = Atomic::from(42u32);
+        assert_eq!(b.load(Ordering::SeqCst), 42);
+    }
+
+    // `Debug`'s `{:?}` formatting needs `std::format!` to assert against --
+    // gated the same way the rest of the crate gates on the `std` feature.
+    #[cfg(all(feature = "std", target_has_atomic = "32"))]
+    #[test]
+    fn debug_formats_as_loaded_value() {
+        let a = Atomic::from(42u32);
+        assert_eq!(std::format!("{:?}", a), "Atomic(42)");
+    }
+
+    #[test]
+    fn locked_load_store_swap_compare_exchange() {
+        let a = Atomic::from(Point { x: 1, y: 2 });
+        assert_eq!(a.load(Ordering::SeqCst), Point { x: 1, y: 2 });
+
+        a.store(Point { x: 3, y: 4 }, Ordering::SeqCst);
+        assert_eq!(a.load(Ordering::SeqCst), Point { x: 3, y: 4 });
+
+        assert_eq!(a.swap(Point { x: 5, y: 6 }, Ordering::SeqCst), Point { x: 3, y: 4 });
+
+        assert_eq!(
+            a.compare_exchange(
+                Point { x: 5, y: 6 },
+                Point { x: 7, y: 8 },
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ),
+            Ok(Point { x: 5, y: 6 }),
+        );
+        assert_eq!(
+            a.compare_exchange(
+                Point { x: 5, y: 6 },
+                Point { x: 9, y: 9 },
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ),
+            Err(Point { x: 7, y: 8 }),
+        );
+        assert_eq!(a.load(Ordering::SeqCst), Point { x: 7, y: 8 });
+    }
+
+    // Round-trips for both shapes `#[derive(Atom)]` supports -- this is
+    // the test that would have caught the derived-enum `unpack` failing
+    // to compile at all (E0015, non-const `unreachable!(..)`).
+    #[cfg(feature = "derive")]
+    mod derive_roundtrip {
+        use super::*;
+
+        #[derive(Atom, Clone, Copy, PartialEq, Debug)]
+        #[repr(u8)]
+        enum Light {
+            Red,
+            Yellow,
+            Green,
+        }
+
+        #[cfg(target_has_atomic = "8")]
+        #[test]
+        fn enum_roundtrip() {
+            let a = Atomic::new(Light::Red);
+            assert_eq!(a.load(Ordering::SeqCst), Light::Red);
+            a.store(Light::Green, Ordering::SeqCst);
+            assert_eq!(a.load(Ordering::SeqCst), Light::Green);
+        }
+
+        #[derive(Atom, Clone, Copy, PartialEq, Debug)]
+        struct Id(u32);
+
+        #[cfg(target_has_atomic = "32")]
+        #[test]
+        fn newtype_roundtrip() {
+            let a = Atomic::new(Id(1));
+            assert_eq!(a.load(Ordering::SeqCst), Id(1));
+            a.store(Id(2), Ordering::SeqCst);
+            assert_eq!(a.load(Ordering::SeqCst), Id(2));
+        }
+
+        // Exercises the forwarded `IdentityPacked` impl: without it,
+        // `Atomic<Id>::fetch_add` wouldn't even compile.
+        #[cfg(target_has_atomic = "32")]
+        #[test]
+        fn newtype_fetch_add_uses_forwarded_identity_packing() {
+            let a = Atomic::new(Id(1));
+            assert_eq!(a.fetch_add(Id(4), Ordering::SeqCst), Id(1));
+            assert_eq!(a.load(Ordering::SeqCst), Id(5));
+        }
+    }
+}